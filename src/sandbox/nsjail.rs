@@ -1,8 +1,36 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::fmt;
+use std::io;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
+#[derive(Debug)]
+pub enum NSMountError {
+    /// The mount spec was neither `src` nor `src:dst`
+    InvalidSpec(String),
+    /// `mandatory` was set but the source path doesn't exist
+    MissingSource(PathBuf),
+    /// Canonicalizing a relative path failed
+    Canonicalize(PathBuf, io::Error),
+}
+
+impl fmt::Display for NSMountError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NSMountError::InvalidSpec(spec) => write!(f, "invalid mount spec: {}", spec),
+            NSMountError::MissingSource(src) => {
+                write!(f, "mandatory mount source does not exist: {}", src.display())
+            }
+            NSMountError::Canonicalize(path, err) => {
+                write!(f, "failed to canonicalize {}: {}", path.display(), err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for NSMountError {}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct NSMount {
     r#type: NSMountType,
@@ -41,6 +69,58 @@ impl NSMount {
         }
     }
 
+    /// Parses a `src` or `src:dst` mount spec (when only one path is given,
+    /// the source and destination are identical).
+    pub fn parse(readwrite: bool, spec: &str) -> Result<NSMount, NSMountError> {
+        let (src, dest) = match spec.split_once(':') {
+            Some((src, dest)) if !src.is_empty() && !dest.is_empty() => (src, dest),
+            Some(_) => return Err(NSMountError::InvalidSpec(spec.to_string())),
+            None if !spec.is_empty() => (spec, spec),
+            None => return Err(NSMountError::InvalidSpec(spec.to_string())),
+        };
+
+        // `dest` is the jail-side path; it has no reason to exist on the
+        // host, so only `src` gets canonicalized and existence-checked.
+        let src_path = PathBuf::from(src);
+        let src = if src_path.is_relative() {
+            std::fs::canonicalize(&src_path)
+                .map_err(|e| NSMountError::Canonicalize(src_path.clone(), e))?
+        } else {
+            src_path
+        };
+
+        // `bind()` mounts are mandatory by default; reject a spec whose
+        // source doesn't exist rather than letting it become a broken
+        // bind mount inside the jail.
+        if !src.exists() {
+            return Err(NSMountError::MissingSource(src));
+        }
+
+        let dest = PathBuf::from(dest);
+        let dest = if dest.is_relative() {
+            std::env::current_dir()
+                .map_err(|e| NSMountError::Canonicalize(dest.clone(), e))?
+                .join(dest)
+        } else {
+            dest
+        };
+
+        let mut mount = NSMount::bind(src, dest);
+        mount.readwrite = readwrite;
+
+        Ok(mount)
+    }
+
+    /// Returns read-only bind mounts for the standard system directories,
+    /// skipping any that don't exist on the host
+    pub fn defaults() -> Vec<NSMount> {
+        ["/bin", "/sbin", "/usr", "/etc", "/lib", "/lib64"]
+            .iter()
+            .filter(|p| Path::new(p).exists())
+            .map(|p| NSMount::readonly(*p, *p))
+            .collect()
+    }
+
     pub fn make_readonly(&mut self) -> &mut NSMount {
         self.readwrite = false;
         self
@@ -61,6 +141,13 @@ impl NSMount {
         self
     }
 
+    fn dest(&self) -> &Path {
+        match &self.r#type {
+            NSMountType::BindMount { dest, .. } => dest,
+            NSMountType::TmpFs { dest } => dest,
+        }
+    }
+
     fn to_write_arg(&self) -> (&'static str, String) {
         match &self.r#type {
             NSMountType::BindMount { src, dest } => {
@@ -112,6 +199,109 @@ impl Into<NSSymlink> for (&'static str, &'static str) {
     }
 }
 
+/// A single line of `/proc/mounts`: source, target, fstype, and the
+/// (unparsed) comma-separated mount options.
+#[derive(Debug, Clone)]
+pub struct Mount {
+    pub source: String,
+    pub target: String,
+    pub fstype: String,
+    pub options: String,
+}
+
+impl Mount {
+    /// Reads and parses `/proc/mounts`
+    pub fn read_live() -> io::Result<Vec<Mount>> {
+        let contents = std::fs::read_to_string("/proc/mounts")?;
+        Ok(Self::parse(&contents))
+    }
+
+    fn parse(contents: &str) -> Vec<Mount> {
+        contents
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.split_whitespace();
+                Some(Mount {
+                    source: fields.next()?.to_string(),
+                    target: fields.next()?.to_string(),
+                    fstype: fields.next()?.to_string(),
+                    options: fields.next()?.to_string(),
+                })
+            })
+            .collect()
+    }
+
+    pub fn is_target_mounted(mounts: &[Mount], target: &Path) -> bool {
+        mounts.iter().any(|m| Path::new(&m.target) == target)
+    }
+
+    pub fn is_source_mounted(mounts: &[Mount], source: &Path) -> bool {
+        mounts.iter().any(|m| Path::new(&m.source) == source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RLimit {
+    /// `--rlimit_as`, the address space (virtual memory) limit
+    As,
+    /// `--rlimit_fsize`, the max file size a jailed process may create
+    FSize,
+    /// `--rlimit_nofile`, the max number of open file descriptors
+    NoFile,
+}
+
+impl RLimit {
+    fn to_arg(self) -> &'static str {
+        match self {
+            RLimit::As => "--rlimit_as",
+            RLimit::FSize => "--rlimit_fsize",
+            RLimit::NoFile => "--rlimit_nofile",
+        }
+    }
+}
+
+/// The lowest nsjail version winecellar's builder is known to generate
+/// valid arguments for.
+const MIN_SUPPORTED_VERSION: NsjailVersion = NsjailVersion { major: 3, minor: 0 };
+
+/// A comparable `(major, minor)` nsjail version, as reported by
+/// `nsjail --help`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct NsjailVersion {
+    pub major: u32,
+    pub minor: u32,
+}
+
+impl NsjailVersion {
+    /// Parses the `Version: X.Y` banner line nsjail prints as part of its
+    /// `--help` output. Anchored on that line rather than scanned across
+    /// the whole help text, which is full of other dotted numeric tokens
+    /// (default bind addresses, example flag values) that aren't versions.
+    fn parse(text: &str) -> Option<NsjailVersion> {
+        let banner = text
+            .lines()
+            .find_map(|line| line.trim().strip_prefix("Version:"))?;
+
+        let digits: String = banner
+            .trim()
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.')
+            .collect();
+
+        let mut parts = digits.splitn(3, '.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        Some(NsjailVersion { major, minor })
+    }
+}
+
+impl fmt::Display for NsjailVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct NSJail {
     mounts: Vec<NSMount>,
     links: Vec<NSSymlink>,
@@ -119,30 +309,225 @@ pub struct NSJail {
     env: HashMap<String, String>,
     user: u64,
     group: u64,
+
+    #[serde(default)]
+    disable_network: bool,
+    #[serde(default)]
+    hostname: Option<String>,
+    #[serde(default)]
+    rlimits: HashMap<RLimit, u64>,
+    #[serde(default)]
+    binary: Option<PathBuf>,
+}
+
+#[derive(Debug)]
+pub enum NSJailError {
+    Io(io::Error),
+    Profile(toml::de::Error),
+    /// A `mandatory` mount's destination never showed up in `/proc/mounts`
+    MountNotVerified(PathBuf),
+    /// No `nsjail` binary was found at the override path or on `PATH`
+    BinaryNotFound,
+    /// `nsjail --help` didn't report a version winecellar could parse
+    VersionUnparsable,
+    /// The resolved nsjail binary is older than `MIN_SUPPORTED_VERSION`
+    UnsupportedVersion(NsjailVersion),
+}
+
+impl fmt::Display for NSJailError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NSJailError::Io(err) => write!(f, "I/O error: {}", err),
+            NSJailError::Profile(err) => write!(f, "failed to parse profile: {}", err),
+            NSJailError::MountNotVerified(dest) => {
+                write!(f, "mandatory mount never appeared in /proc/mounts: {}", dest.display())
+            }
+            NSJailError::BinaryNotFound => {
+                write!(f, "nsjail binary not found (set an override path or install it on PATH)")
+            }
+            NSJailError::VersionUnparsable => {
+                write!(f, "could not parse a version out of `nsjail --help` output")
+            }
+            NSJailError::UnsupportedVersion(version) => write!(
+                f,
+                "nsjail {} is too old, need at least {}",
+                version, MIN_SUPPORTED_VERSION
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NSJailError {}
+
+impl From<io::Error> for NSJailError {
+    fn from(err: io::Error) -> Self {
+        NSJailError::Io(err)
+    }
+}
+
+impl From<toml::de::Error> for NSJailError {
+    fn from(err: toml::de::Error) -> Self {
+        NSJailError::Profile(err)
+    }
 }
 
 #[allow(dead_code)]
 impl NSJail {
-    pub fn command(self) -> Command {
-        let mut cmd = Command::new("/usr/bin/nsjail");
+    /// Loads a sandbox definition (mounts, symlinks, env map, uid/gid)
+    /// from a TOML profile
+    pub fn from_profile<P: AsRef<Path>>(path: P) -> Result<NSJail, NSJailError> {
+        let contents = std::fs::read_to_string(path)?;
+        let jail = toml::from_str(&contents)?;
+        Ok(jail)
+    }
+
+    /// Parses a `KEY=VALUE` env-file (blank lines and `#` comments are
+    /// ignored, surrounding quotes on the value are trimmed) and merges
+    /// it into the jail's environment map
+    pub fn env_file<P: AsRef<Path>>(&mut self, path: P) -> Result<&mut NSJail, NSJailError> {
+        let contents = std::fs::read_to_string(path)?;
+
+        for line in contents.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if let Some((key, value)) = line.split_once('=') {
+                let value = value.trim().trim_matches('"').trim_matches('\'');
+                self.env.insert(key.trim().to_string(), value.to_string());
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Sets an explicit path to the `nsjail` binary, overriding the
+    /// `PATH` lookup `resolve_binary()` otherwise falls back to.
+    pub fn binary_path<T: Into<PathBuf>>(&mut self, path: T) -> &mut NSJail {
+        self.binary = Some(path.into());
+        self
+    }
+
+    /// Resolves the `nsjail` binary to run: an explicit override set via
+    /// `.binary_path()` if present, otherwise a `PATH` lookup.
+    pub fn resolve_binary(&self) -> Result<PathBuf, NSJailError> {
+        if let Some(path) = &self.binary {
+            if path.is_file() {
+                return Ok(path.clone());
+            }
+            return Err(NSJailError::BinaryNotFound);
+        }
+
+        std::env::var_os("PATH")
+            .iter()
+            .flat_map(std::env::split_paths)
+            .map(|dir| dir.join("nsjail"))
+            .find(|candidate| candidate.is_file())
+            .ok_or(NSJailError::BinaryNotFound)
+    }
+
+    /// Runs `nsjail --help` against the resolved binary and parses its
+    /// reported version, erroring if it's older than `MIN_SUPPORTED_VERSION`
+    pub fn preflight(&self) -> Result<NsjailVersion, NSJailError> {
+        let binary = self.resolve_binary()?;
+        let output = Command::new(&binary).arg("--help").output()?;
+
+        let text = format!(
+            "{}{}",
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        let version = NsjailVersion::parse(&text).ok_or(NSJailError::VersionUnparsable)?;
+
+        if version < MIN_SUPPORTED_VERSION {
+            return Err(NSJailError::UnsupportedVersion(version));
+        }
+
+        Ok(version)
+    }
+
+    pub fn command(&self) -> Result<Command, NSJailError> {
+        let binary = self.resolve_binary()?;
+        let mut cmd = Command::new(binary);
 
         cmd.arg("--user").arg(self.user.to_string());
         cmd.arg("--group").arg(self.group.to_string());
 
         self.mounts
-            .into_iter()
+            .iter()
             .map(|x| x.to_write_arg())
             .for_each(|x| {
                 cmd.arg(x.0).arg(x.1);
             });
 
-        // TODO Make it so we don't gotta do this... somehow
-        cmd.arg("--keep_env");
-        cmd.envs(self.env);
+        for link in &self.links {
+            cmd.arg("--symlink")
+                .arg(format!("{}:{}", link.src.display(), link.dest.display()));
+        }
+
+        if self.disable_network {
+            cmd.arg("--disable_clone_newnet");
+        }
+
+        if let Some(hostname) = &self.hostname {
+            cmd.arg("--hostname").arg(hostname);
+        }
+
+        for (limit, value) in &self.rlimits {
+            cmd.arg(limit.to_arg()).arg(value.to_string());
+        }
+
+        for (key, value) in &self.env {
+            cmd.arg("--env").arg(format!("{}={}", key, value));
+        }
 
         // Make sure that the caller can pass arguments without worry
         cmd.arg("--");
-        cmd
+        Ok(cmd)
+    }
+
+    /// Disables network namespace cloning, cutting the jailed process off
+    /// from the network (`--disable_clone_newnet`)
+    pub fn disable_network(&mut self) -> &mut NSJail {
+        self.disable_network = true;
+        self
+    }
+
+    /// Sets the hostname visible inside the jail (`--hostname`)
+    pub fn hostname<T: Into<String>>(&mut self, hostname: T) -> &mut NSJail {
+        self.hostname = Some(hostname.into());
+        self
+    }
+
+    /// Sets a resource limit (e.g. memory, file size, open file descriptors)
+    /// for the jailed process
+    pub fn rlimit(&mut self, limit: RLimit, value: u64) -> &mut NSJail {
+        self.rlimits.insert(limit, value);
+        self
+    }
+
+    /// Builds an `NSJail` pre-populated with `NSMount::defaults()`
+    pub fn with_system_defaults() -> NSJail {
+        let mut jail = NSJail::default();
+        jail.mounts.extend(NSMount::defaults());
+        jail
+    }
+
+    /// Checks every `NSMount`'s destination against the live mount table
+    /// in `/proc/mounts` and reports the first `mandatory` mount that
+    /// failed to materialize
+    pub fn verify_mounts(&self) -> Result<(), NSJailError> {
+        let live = Mount::read_live()?;
+
+        for mount in &self.mounts {
+            if mount.mandatory && !Mount::is_target_mounted(&live, mount.dest()) {
+                return Err(NSJailError::MountNotVerified(mount.dest().to_path_buf()));
+            }
+        }
+
+        Ok(())
     }
 
     pub fn mount(&mut self, mount: NSMount) -> &mut NSJail {
@@ -165,6 +550,181 @@ impl Default for NSJail {
             env: HashMap::new(),
             user: 1000,
             group: 1000,
+
+            disable_network: false,
+            hostname: None,
+            rlimits: HashMap::new(),
+            binary: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_single_path_uses_it_as_both_src_and_dest() {
+        let mount = NSMount::parse(true, "/tmp").unwrap();
+        assert_eq!(mount.dest(), Path::new("/tmp"));
+    }
+
+    #[test]
+    fn parse_relative_dest_does_not_require_it_to_exist() {
+        let mount = NSMount::parse(true, "/tmp:relative_dest_that_does_not_exist_xyz").unwrap();
+        assert_eq!(
+            mount.dest(),
+            std::env::current_dir()
+                .unwrap()
+                .join("relative_dest_that_does_not_exist_xyz")
+        );
+    }
+
+    #[test]
+    fn parse_missing_src_is_an_error() {
+        let err = NSMount::parse(true, "/no/such/path/xyz:/tmp").unwrap_err();
+        assert!(matches!(err, NSMountError::MissingSource(_)));
+    }
+
+    #[test]
+    fn parse_empty_spec_is_invalid() {
+        let err = NSMount::parse(true, "").unwrap_err();
+        assert!(matches!(err, NSMountError::InvalidSpec(_)));
+    }
+
+    #[test]
+    fn parse_empty_side_of_colon_is_invalid() {
+        let err = NSMount::parse(true, "/tmp:").unwrap_err();
+        assert!(matches!(err, NSMountError::InvalidSpec(_)));
+    }
+
+    #[test]
+    fn mount_parse_splits_proc_mounts_fields() {
+        let mounts = Mount::parse("/dev/sda1 / ext4 rw,relatime 0 0\n");
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].source, "/dev/sda1");
+        assert_eq!(mounts[0].target, "/");
+        assert_eq!(mounts[0].fstype, "ext4");
+        assert_eq!(mounts[0].options, "rw,relatime");
+    }
+
+    #[test]
+    fn mount_parse_skips_malformed_lines() {
+        let mounts = Mount::parse("\nonly two fields\nnone /tmp tmpfs rw 0 0\n");
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].target, "/tmp");
+    }
+
+    #[test]
+    fn is_target_and_source_mounted() {
+        let mounts = Mount::parse("none /tmp tmpfs rw 0 0\n");
+        assert!(Mount::is_target_mounted(&mounts, Path::new("/tmp")));
+        assert!(!Mount::is_target_mounted(&mounts, Path::new("/other")));
+        assert!(Mount::is_source_mounted(&mounts, Path::new("none")));
+        assert!(!Mount::is_source_mounted(&mounts, Path::new("/dev/sda1")));
+    }
+
+    #[test]
+    fn env_file_ignores_blanks_and_comments_and_trims_quotes() {
+        let mut file = std::env::temp_dir();
+        file.push(format!("winecellar-env-file-test-{:?}", std::thread::current().id()));
+        std::fs::write(&file, "# a comment\n\nWINEPREFIX=\"/home/user/.wine\"\nDISPLAY=:0\n").unwrap();
+
+        let mut jail = NSJail::default();
+        jail.env_file(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(jail.env.get("WINEPREFIX").unwrap(), "/home/user/.wine");
+        assert_eq!(jail.env.get("DISPLAY").unwrap(), ":0");
+        assert_eq!(jail.env.len(), 2);
+    }
+
+    #[test]
+    fn nsjail_version_parses_the_version_banner() {
+        let help = "Jailing processes with Linux namespaces\nVersion: 3.4\n\nUsage: nsjail [options] -- path_to_command\n --bindmount 0.0.0.0:1234\n";
+        let version = NsjailVersion::parse(help).unwrap();
+        assert_eq!(version, NsjailVersion { major: 3, minor: 4 });
+    }
+
+    #[test]
+    fn nsjail_version_ignores_unrelated_dotted_tokens_before_banner() {
+        let help = " --port 0.0.0.0:1234 default\nVersion: 3.10\n";
+        let version = NsjailVersion::parse(help).unwrap();
+        assert_eq!(version, NsjailVersion { major: 3, minor: 10 });
+    }
+
+    #[test]
+    fn nsjail_version_returns_none_without_a_banner() {
+        assert!(NsjailVersion::parse("--bindmount 0.0.0.0:1234\n").is_none());
+    }
+
+    #[test]
+    fn nsjail_version_ordering_respects_min_supported() {
+        assert!(NsjailVersion { major: 2, minor: 9 } < MIN_SUPPORTED_VERSION);
+        assert!(NsjailVersion { major: 3, minor: 1 } >= MIN_SUPPORTED_VERSION);
+    }
+
+    #[test]
+    fn command_emits_symlinks_network_hostname_and_rlimits() {
+        let mut jail = NSJail::default();
+        jail.binary_path("/bin/true");
+        jail.symlink(("a", "b"));
+        jail.disable_network();
+        jail.hostname("wine-sandbox");
+        jail.rlimit(RLimit::As, 1024);
+
+        let cmd = jail.command().unwrap();
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_str().unwrap()).collect();
+
+        assert!(args.windows(2).any(|w| w == ["--symlink", "a:b"]));
+        assert!(args.contains(&"--disable_clone_newnet"));
+        assert!(args.windows(2).any(|w| w == ["--hostname", "wine-sandbox"]));
+        assert!(args.windows(2).any(|w| w == ["--rlimit_as", "1024"]));
+    }
+
+    #[test]
+    fn nsjail_round_trips_through_toml_including_rlimits() {
+        let mut jail = NSJail::default();
+        jail.mount(NSMount::readonly("/usr", "/usr"));
+        jail.symlink(("a", "b"));
+        jail.hostname("wine-sandbox");
+        jail.rlimit(RLimit::As, 1024);
+        jail.rlimit(RLimit::NoFile, 256);
+
+        let toml = toml::to_string(&jail).unwrap();
+
+        let mut file = std::env::temp_dir();
+        file.push(format!("winecellar-profile-test-{:?}", std::thread::current().id()));
+        std::fs::write(&file, &toml).unwrap();
+        let loaded = NSJail::from_profile(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        assert_eq!(loaded.user, jail.user);
+        assert_eq!(loaded.group, jail.group);
+        assert_eq!(loaded.hostname, jail.hostname);
+        assert_eq!(loaded.mounts.len(), 1);
+        assert_eq!(loaded.links.len(), 1);
+        assert_eq!(loaded.rlimits.get(&RLimit::As), Some(&1024));
+        assert_eq!(loaded.rlimits.get(&RLimit::NoFile), Some(&256));
+    }
+
+    #[test]
+    fn command_emits_env_args_and_drops_keep_env() {
+        let mut file = std::env::temp_dir();
+        file.push(format!("winecellar-env-file-command-test-{:?}", std::thread::current().id()));
+        std::fs::write(&file, "WINEPREFIX=/home/user/.wine\n").unwrap();
+
+        let mut jail = NSJail::default();
+        jail.binary_path("/bin/true");
+        jail.env_file(&file).unwrap();
+        std::fs::remove_file(&file).unwrap();
+
+        let cmd = jail.command().unwrap();
+        let args: Vec<_> = cmd.get_args().map(|a| a.to_str().unwrap()).collect();
+
+        assert!(args
+            .windows(2)
+            .any(|w| w == ["--env", "WINEPREFIX=/home/user/.wine"]));
+        assert!(!args.contains(&"--keep_env"));
+    }
+}